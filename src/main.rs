@@ -1,16 +1,28 @@
+use specs::hibitset::BitSetLike;
 use specs::prelude::*;
+use specs::saveload::{
+    DeserializeComponents, MarkedBuilder, SerializeComponents, SimpleMarker,
+    SimpleMarkerAllocator,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
 use tetra::graphics::{self, Color, Texture, DrawParams, Rectangle};
+use tetra::input::{self, Key};
 use tetra::{Context, ContextBuilder, State};
 use tetra::time::Timestep;
 use tetra::math::Vec2;
 
 const SPRITE_SIZE: i32 = 20;
 const SCREEN_SIZE: i32 = 20;
-const INITIAL_TAIL: usize = 5;
+const SAVE_PATH: &str = "./save.ron";
 
 // A component contains data
 // which is associated with an entity.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Velocity(Vec2<i32>);
 
 impl Component for Velocity {
@@ -18,7 +30,7 @@ impl Component for Velocity {
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Lifetime(usize);
 
 impl Component for Lifetime {
@@ -26,22 +38,183 @@ impl Component for Lifetime {
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Position(Vec2<i32>);
 
 impl Component for Position {
-    type Storage = VecStorage<Self>;
+    // Flagged so systems can react to inserts/modifications/removals
+    // without rescanning every entity each tick - see `ChangeTrackingSystem`.
+    // Note the invariant: a modification is only flagged when `Position` is
+    // reached through `get_mut`/`restrict_mut` or a `join` over `&mut` storage
+    // (as in `MovementSystem`), not through raw storage access.
+    type Storage = FlaggedStorage<Self, VecStorage<Self>>;
 }
 
-#[derive(Debug)]
+fn default_rect() -> Rectangle {
+    Rectangle::new(0.0, 0.0, 1.0, 1.0)
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Sprite {
+    // The clip rect isn't saved, so a save/load round trip
+    // just resets the clip to the default frame.
+    #[serde(skip, default = "default_rect")]
     rect: Rectangle,
+    // Multiplier on top of the grid draw scale (`SPRITE_SIZE - 1`), so effects can draw larger
+    // or smaller than a regular grid sprite.
+    #[serde(default = "default_scale")]
+    scale: f32,
 }
 
 impl Component for Sprite {
     type Storage = VecStorage<Self>;
 }
 
+// Tags the entities that `save_world`/`load_world` persist.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+struct SaveTag;
+
+type SaveMarker = SimpleMarker<SaveTag>;
+type SaveMarkerAllocator = SimpleMarkerAllocator<SaveTag>;
+
+#[derive(Debug)]
+enum SaveLoadError {
+    Io(std::io::Error),
+    Ron(ron::Error),
+}
+
+impl fmt::Display for SaveLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveLoadError::Io(e) => write!(f, "{}", e),
+            SaveLoadError::Ron(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SaveLoadError {}
+
+impl From<std::io::Error> for SaveLoadError {
+    fn from(e: std::io::Error) -> Self {
+        SaveLoadError::Io(e)
+    }
+}
+
+impl From<ron::Error> for SaveLoadError {
+    fn from(e: ron::Error) -> Self {
+        SaveLoadError::Ron(e)
+    }
+}
+
+// Required by `SerializeComponents`/`DeserializeComponents`, which are generic
+// over components that can fail to convert; ours never do.
+impl From<std::convert::Infallible> for SaveLoadError {
+    fn from(e: std::convert::Infallible) -> Self {
+        match e {}
+    }
+}
+
+// Cycles a `Sprite`'s clip rect through `frames` over time, ticked
+// once per fixed update rather than by wall-clock time.
+#[derive(Debug)]
+struct Animation {
+    current: u32,
+    limit: u32,
+    frames: Vec<Rectangle>,
+    frame_index: usize,
+    looping: bool,
+}
+
+impl Animation {
+    fn new(limit: u32, frames: Vec<Rectangle>, looping: bool) -> Self {
+        assert!(!frames.is_empty(), "Animation needs at least one frame");
+
+        Self {
+            current: 0,
+            limit,
+            frames,
+            frame_index: 0,
+            looping,
+        }
+    }
+}
+
+impl Component for Animation {
+    type Storage = VecStorage<Self>;
+}
+
+struct AnimationSystem;
+
+impl<'a> System<'a> for AnimationSystem {
+    type SystemData = (WriteStorage<'a, Sprite>, WriteStorage<'a, Animation>);
+
+    fn run(&mut self, (mut sprites, mut animations): Self::SystemData) {
+        for (sprite, animation) in (&mut sprites, &mut animations).join() {
+            animation.current += 1;
+
+            if animation.current >= animation.limit {
+                animation.current = 0;
+
+                if animation.frame_index + 1 < animation.frames.len() {
+                    animation.frame_index += 1;
+                } else if animation.looping {
+                    animation.frame_index = 0;
+                }
+            }
+
+            // `frames` is guaranteed non-empty by `Animation::new`, but an
+            // `Animation` built some other way (e.g. a struct literal) might not be -
+            // skip the tick rather than panic on an out-of-range index.
+            if let Some(rect) = animation.frames.get(animation.frame_index) {
+                sprite.rect = *rect;
+            }
+        }
+    }
+}
+
+// Marker component for the entity steered by the keyboard.
+#[derive(Debug, Default)]
+struct Player;
+
+impl Component for Player {
+    type Storage = NullStorage<Self>;
+}
+
+// Populated by `GameState::update` from the (non-`Send`) `Context`
+// before `dispatch` is called, so systems never need to touch it directly.
+#[derive(Debug, Default)]
+struct InputState {
+    direction: Option<Vec2<i32>>,
+}
+
+struct InputSystem;
+
+impl<'a> System<'a> for InputSystem {
+    type SystemData = (
+        WriteStorage<'a, Velocity>,
+        ReadStorage<'a, Player>,
+        Read<'a, InputState>,
+    );
+
+    fn run(&mut self, (mut velocities, players, input): Self::SystemData) {
+        let direction = match input.direction {
+            Some(direction) => direction,
+            None => return,
+        };
+
+        for (velocity, _) in (&mut velocities, &players).join() {
+            // Ignore input that would reverse the current heading.
+            if direction != -velocity.0 {
+                velocity.0 = direction;
+            }
+        }
+    }
+}
+
 struct MovementSystem;
 
 impl<'a> System<'a> for MovementSystem {
@@ -66,15 +239,396 @@ struct LifetimeSystem;
 impl<'a> System<'a> for LifetimeSystem {
     type SystemData = (
         WriteStorage<'a, Lifetime>,
-        Entities<'a>
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Velocity>,
+        ReadStorage<'a, Particle>,
+        Write<'a, EffectRequests>,
+        Entities<'a>,
     );
 
-    fn run(&mut self, (mut lifetimes, entities): Self::SystemData) {
+    fn run(&mut self, (mut lifetimes, positions, velocities, particles, mut effects, entities): Self::SystemData) {
         for (lifetime, entity) in (&mut lifetimes, &*entities).join() {
             if lifetime.0 > 0 {
                 lifetime.0 -= 1;
             } else {
-                entities.delete(entity);
+                // Leave a puff behind wherever a timed-out entity despawns -
+                // but not for a `Particle` itself, or its own explosion would
+                // keep re-triggering another explosion forever.
+                if !particles.contains(entity) {
+                    if let Some(position) = positions.get(entity) {
+                        effects.push(EffectRequest {
+                            effect_name: "explosion".to_string(),
+                            position: position.0,
+                            source_velocity: velocities.get(entity).map_or(Vec2::new(0, 0), |v| v.0),
+                        });
+                    }
+                }
+
+                entities.delete(entity).expect("entity was just joined on, so it must still be alive");
+            }
+        }
+    }
+}
+
+const EFFECTS_PATH: &str = "./content/effects.toml";
+
+// A clip rect as written in `effects.toml`, with named fields; effect
+// definitions go through this before becoming a `Rectangle`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct ClipRect {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+impl From<ClipRect> for Rectangle {
+    fn from(clip: ClipRect) -> Self {
+        Rectangle::new(clip.x, clip.y, clip.width, clip.height)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum LifetimeSpec {
+    Fixed(usize),
+    Range([usize; 2]),
+}
+
+impl LifetimeSpec {
+    fn roll(&self) -> usize {
+        match *self {
+            LifetimeSpec::Fixed(ticks) => ticks,
+            LifetimeSpec::Range([min, max]) => rand::thread_rng().gen_range(min..=max),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+enum InheritVelocity {
+    Target,
+    None,
+    Scale(f32),
+}
+
+// Rounds away from zero rather than to the nearest integer, so a nonzero
+// input never collapses to 0 - used for `InheritVelocity::Scale`, where a
+// sub-unit factor (e.g. 0.25) would otherwise always round to (0, 0) on
+// this integer grid, making "Scale" indistinguishable from "None".
+fn round_away_from_zero(value: f32) -> i32 {
+    if value == 0.0 {
+        0
+    } else {
+        (value.signum() * value.abs().ceil()) as i32
+    }
+}
+
+impl InheritVelocity {
+    // Derives the spawned particle's velocity from the entity that
+    // requested it, with a small random angular jitter when scaled.
+    fn apply(&self, source_velocity: Vec2<i32>) -> Vec2<i32> {
+        match *self {
+            InheritVelocity::Target => source_velocity,
+            InheritVelocity::None => Vec2::new(0, 0),
+            InheritVelocity::Scale(factor) => {
+                let x = source_velocity.x as f32 * factor;
+                let y = source_velocity.y as f32 * factor;
+
+                let jitter = rand::thread_rng().gen_range(-0.25_f32..=0.25_f32);
+                let cos = jitter.cos();
+                let sin = jitter.sin();
+
+                // Only components that were nonzero before the jitter round
+                // away from zero; otherwise any jitter would become a full
+                // unit step sideways.
+                let round = |value: f32, unjittered: f32| {
+                    if unjittered == 0.0 {
+                        value.round() as i32
+                    } else {
+                        round_away_from_zero(value)
+                    }
+                };
+
+                Vec2::new(
+                    round(x * cos - y * sin, x),
+                    round(x * sin + y * cos, y),
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EffectDef {
+    sprite: ClipRect,
+    size: f32,
+    lifetime: LifetimeSpec,
+    inherit_velocity: InheritVelocity,
+}
+
+// Loaded once at startup; `EffectSystem` looks effects up by name.
+#[derive(Debug, Default)]
+struct Effects(HashMap<String, EffectDef>);
+
+fn load_effects(path: &str) -> HashMap<String, EffectDef> {
+    let contents = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", path, e));
+
+    toml::from_str(&contents).unwrap_or_else(|e| panic!("failed to parse {}: {}", path, e))
+}
+
+// Pushed to by other systems whenever they want a particle spawned;
+// drained by `EffectSystem` once per tick.
+#[derive(Debug, Clone)]
+struct EffectRequest {
+    effect_name: String,
+    position: Vec2<i32>,
+    source_velocity: Vec2<i32>,
+}
+
+#[derive(Debug, Default)]
+struct EffectRequests(Vec<EffectRequest>);
+
+impl EffectRequests {
+    fn push(&mut self, request: EffectRequest) {
+        self.0.push(request);
+    }
+}
+
+// Marker for entities spawned by `EffectSystem` - `LifetimeSystem` checks
+// this so an expiring particle doesn't itself trigger another effect,
+// which would otherwise spawn explosions forever at the same cell.
+#[derive(Debug, Default)]
+struct Particle;
+
+impl Component for Particle {
+    type Storage = NullStorage<Self>;
+}
+
+struct EffectSystem;
+
+impl<'a> System<'a> for EffectSystem {
+    type SystemData = (
+        Write<'a, EffectRequests>,
+        Read<'a, Effects>,
+        Entities<'a>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Velocity>,
+        WriteStorage<'a, Sprite>,
+        WriteStorage<'a, Lifetime>,
+        WriteStorage<'a, Particle>,
+    );
+
+    fn run(
+        &mut self,
+        (mut requests, effects, entities, mut positions, mut velocities, mut sprites, mut lifetimes, mut particles): Self::SystemData,
+    ) {
+        for request in requests.0.drain(..) {
+            let def = match effects.0.get(&request.effect_name) {
+                Some(def) => def,
+                None => continue,
+            };
+
+            let entity = entities.create();
+            positions.insert(entity, Position(request.position)).unwrap();
+            velocities
+                .insert(entity, Velocity(def.inherit_velocity.apply(request.source_velocity)))
+                .unwrap();
+            sprites
+                .insert(
+                    entity,
+                    Sprite {
+                        rect: def.sprite.into(),
+                        scale: def.size,
+                    },
+                )
+                .unwrap();
+            lifetimes.insert(entity, Lifetime(def.lifetime.roll())).unwrap();
+            particles.insert(entity, Particle).unwrap();
+        }
+    }
+}
+
+// Back-to-front draw order; entities without one draw at layer 0.
+#[derive(Debug)]
+struct Layer(i32);
+
+impl Component for Layer {
+    type Storage = VecStorage<Self>;
+}
+
+// `Context` and `Texture` aren't `Send`, so this can't be a normal specs
+// resource - `GameState::draw` stashes raw pointers into it for the
+// duration of a single `render_dispatcher.dispatch` call, and clears them
+// immediately after. `RenderSystem` only ever runs on the main thread
+// (it's registered with `with_thread_local`), so the pointers are never
+// read from another thread or outside that window.
+struct RenderState {
+    ctx: *mut Context,
+    texture: *const Texture,
+}
+
+impl Default for RenderState {
+    fn default() -> Self {
+        Self {
+            ctx: std::ptr::null_mut(),
+            texture: std::ptr::null(),
+        }
+    }
+}
+
+unsafe impl Send for RenderState {}
+unsafe impl Sync for RenderState {}
+
+struct RenderSystem;
+
+impl<'a> System<'a> for RenderSystem {
+    type SystemData = (
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Sprite>,
+        ReadStorage<'a, Layer>,
+        Write<'a, RenderState>,
+    );
+
+    fn run(&mut self, (positions, sprites, layers, render_state): Self::SystemData) {
+        let ctx = match unsafe { render_state.ctx.as_mut() } {
+            Some(ctx) => ctx,
+            None => return,
+        };
+        let texture = match unsafe { render_state.texture.as_ref() } {
+            Some(texture) => texture,
+            None => return,
+        };
+
+        let mut drawables: Vec<_> = (&positions, &sprites, layers.maybe())
+            .join()
+            .map(|(position, sprite, layer)| (layer.map_or(0, |l| l.0), position, sprite))
+            .collect();
+        // Sort back-to-front so higher layers draw on top.
+        drawables.sort_by_key(|(z, _, _)| *z);
+
+        let base_scale = (SPRITE_SIZE - 1) as f32;
+
+        for (_, position, sprite) in drawables {
+            let pos = Vec2::new(((position.0).x * SPRITE_SIZE) as f32, ((position.0).y * SPRITE_SIZE) as f32);
+            let scale = Vec2::new(base_scale * sprite.scale, base_scale * sprite.scale);
+            texture.draw_region(ctx, sprite.rect, DrawParams::new()
+                .position(pos)
+                .scale(scale));
+        }
+    }
+}
+
+// Drains a flagged storage's change channel into index sets a consuming
+// system can `join` against, instead of rescanning every entity.
+fn track_changes<T: Component>(
+    storage: &ReadStorage<T>,
+    reader_id: &mut ReaderId<ComponentEvent>,
+) -> (BitSet, BitSet, BitSet)
+where
+    T::Storage: Tracked,
+{
+    let mut inserted = BitSet::new();
+    let mut modified = BitSet::new();
+    let mut removed = BitSet::new();
+
+    for event in storage.channel().read(reader_id) {
+        match event {
+            ComponentEvent::Inserted(id) => {
+                inserted.add(*id);
+            }
+            ComponentEvent::Modified(id) => {
+                modified.add(*id);
+            }
+            ComponentEvent::Removed(id) => {
+                removed.add(*id);
+            }
+        }
+    }
+
+    (inserted, modified, removed)
+}
+
+// Which `Position` entities changed last tick, refreshed once per tick
+// by `ChangeTrackingSystem`. Consumers `join` against these instead of
+// iterating every `Position` to find the ones that moved.
+#[derive(Default)]
+struct ChangeEvents {
+    position_inserted: BitSet,
+    position_modified: BitSet,
+    position_removed: BitSet,
+}
+
+#[derive(Default)]
+struct ChangeTrackingSystem {
+    position_reader: Option<ReaderId<ComponentEvent>>,
+}
+
+impl<'a> System<'a> for ChangeTrackingSystem {
+    type SystemData = (ReadStorage<'a, Position>, Write<'a, ChangeEvents>);
+
+    fn run(&mut self, (positions, mut events): Self::SystemData) {
+        let reader_id = self
+            .position_reader
+            .as_mut()
+            .expect("ChangeTrackingSystem::setup was not called");
+
+        let (inserted, modified, removed) = track_changes(&positions, reader_id);
+        events.position_inserted = inserted;
+        events.position_modified = modified;
+        events.position_removed = removed;
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        self.position_reader = Some(WriteStorage::<Position>::fetch(world).register_reader());
+    }
+}
+
+// Maps grid cell -> occupying entity. Updated incrementally from
+// `ChangeEvents` instead of being rebuilt from every `Position` each tick.
+#[derive(Default)]
+struct Occupancy {
+    cells: HashMap<(i32, i32), Entity>,
+    // The cell each entity last occupied, keyed by entity id - lets a
+    // `Modified`/`Removed` event evict the stale entry directly instead of
+    // scanning every cell in `cells`.
+    prev_cell: HashMap<u32, (i32, i32)>,
+}
+
+struct OccupancySystem;
+
+impl<'a> System<'a> for OccupancySystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Position>,
+        Read<'a, ChangeEvents>,
+        Write<'a, Occupancy>,
+    );
+
+    fn run(&mut self, (entities, positions, events, mut occupancy): Self::SystemData) {
+        for (entity, position, _) in (&entities, &positions, &events.position_inserted).join() {
+            let cell = ((position.0).x, (position.0).y);
+            occupancy.cells.insert(cell, entity);
+            occupancy.prev_cell.insert(entity.id(), cell);
+        }
+
+        for (entity, position, _) in (&entities, &positions, &events.position_modified).join() {
+            let cell = ((position.0).x, (position.0).y);
+            if let Some(old_cell) = occupancy.prev_cell.get(&entity.id()).copied() {
+                if occupancy.cells.get(&old_cell) == Some(&entity) {
+                    occupancy.cells.remove(&old_cell);
+                }
+            }
+            occupancy.cells.insert(cell, entity);
+            occupancy.prev_cell.insert(entity.id(), cell);
+        }
+
+        for id in (&events.position_removed).iter() {
+            if let Some(old_cell) = occupancy.prev_cell.remove(&id) {
+                if occupancy.cells.get(&old_cell).map(|e| e.id()) == Some(id) {
+                    occupancy.cells.remove(&old_cell);
+                }
             }
         }
     }
@@ -83,6 +637,7 @@ impl<'a> System<'a> for LifetimeSystem {
 struct GameState<'a> {
     world: World,
     dispatcher: Dispatcher<'a, 'a>,
+    render_dispatcher: Dispatcher<'a, 'a>,
     spritesheet: Texture,
 }
 
@@ -96,13 +651,49 @@ impl<'a> GameState<'a> {
         world.register::<Velocity>();
         world.register::<Lifetime>();
         world.register::<Sprite>();
+        world.register::<Animation>();
+        world.register::<Player>();
+        world.register::<Particle>();
+        world.register::<SaveMarker>();
+        world.register::<Layer>();
+        world.insert(InputState::default());
+        world.insert(SaveMarkerAllocator::new());
+        world.insert(Effects(load_effects(EFFECTS_PATH)));
+        world.insert(EffectRequests::default());
+        world.insert(RenderState::default());
+        world.insert(ChangeEvents::default());
+        world.insert(Occupancy::default());
+
+        // This builds a dispatcher.
+        // The third parameter of `with` specifies
+        // logical dependencies on other systems.
+        // Systems with an empty list don't depend on anything.
+        // See the `full` example for dependencies.
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(InputSystem, "input", &[])
+            .with(MovementSystem, "movement", &["input"])
+            .with(LifetimeSystem, "lifetime", &[])
+            .with(AnimationSystem, "animation", &[])
+            .with(EffectSystem, "effects", &[])
+            .with(ChangeTrackingSystem::default(), "change_tracking", &["movement"])
+            .with(OccupancySystem, "occupancy", &["change_tracking"])
+            .build();
+        // This will call the `setup` function of every system - in particular,
+        // `ChangeTrackingSystem::setup` registers its `Position` reader here.
+        // It has to run before any entities are created below, or their
+        // initial `Inserted` events would happen before the reader exists
+        // and `OccupancySystem` would never learn about them.
+        dispatcher.setup(&mut world);
 
         // An entity may or may not contain some component.
+        // No `Lifetime` here - the player is steered by `InputSystem`, not
+        // despawned by `LifetimeSystem`, and should persist indefinitely.
         world.create_entity()
             .with(Velocity(Vec2::new(1, 0)))
             .with(Position(Vec2::new(0, 0)))
-            .with(Lifetime(INITIAL_TAIL))
-            .with(Sprite{rect: Rectangle::new(0.0,0.0,1.0,1.0)})
+            .with(Sprite { rect: Rectangle::new(0.0, 0.0, 1.0, 1.0), scale: 1.0 })
+            .with(Player)
+            .marked::<SaveMarker>()
             .build();
         // world.create_entity().with(Vel(Vec2::new(0.0, 1.0))).with(Pos(Vec2::new(3.0, 2.0))).build();
         // world.create_entity().with(Vel(Vec2::new(-1.0, 2.0))).with(Pos(Vec2::new(5.0, 4.0))).build();
@@ -110,38 +701,128 @@ impl<'a> GameState<'a> {
         // This entity does not have `Vel`, so it won't be dispatched.
         world.create_entity()
             .with(Position(Vec2::new(2, 0)))
-            .with(Sprite{rect: Rectangle::new(0.0,1.0,1.0,1.0)})
+            .with(Sprite { rect: Rectangle::new(0.0, 1.0, 1.0, 1.0), scale: 1.0 })
+            .with(Animation::new(
+                8,
+                vec![
+                    Rectangle::new(0.0, 1.0, 1.0, 1.0),
+                    Rectangle::new(1.0, 1.0, 1.0, 1.0),
+                ],
+                true,
+            ))
+            .marked::<SaveMarker>()
             .build();
 
-        // This builds a dispatcher.
-        // The third parameter of `with` specifies
-        // logical dependencies on other systems.
-        // Since we only have one, we don't depend on anything.
-        // See the `full` example for dependencies.
-        let mut dispatcher = DispatcherBuilder::new()
-            .with(MovementSystem, "movement", &[])
-            .with(LifetimeSystem, "lifetime", &[])
-            //.with(RenderSystem::new(), "renderer", &[])
+        // Rendering has to run on the main thread, since `Context` and
+        // `Texture` aren't `Send` - `with_thread_local` keeps it out of
+        // the parallel `dispatcher` above.
+        let mut render_dispatcher = DispatcherBuilder::new()
+            .with_thread_local(RenderSystem)
             .build();
-        // This will call the `setup` function of every system.
-        // In this example this has no effect since we already registered our components.
-        dispatcher.setup(&mut world);
+        render_dispatcher.setup(&mut world);
+
         let spritesheet = Texture::new(ctx, "./assets/spritesheet.png")?;
 
         Ok(
             Self {
                 world,
                 dispatcher,
+                render_dispatcher,
                 spritesheet
             }
         )
     }
+
+    // Serializes every `SaveMarker`-tagged entity's `Position`, `Velocity`,
+    // `Lifetime` and `Sprite` components to a RON file.
+    #[allow(clippy::type_complexity)]
+    fn save_world(&self, path: &str) -> Result<(), SaveLoadError> {
+        let (entities, markers, positions, velocities, lifetimes, sprites): (
+            Entities,
+            ReadStorage<SaveMarker>,
+            ReadStorage<Position>,
+            ReadStorage<Velocity>,
+            ReadStorage<Lifetime>,
+            ReadStorage<Sprite>,
+        ) = self.world.system_data();
+
+        let writer = BufWriter::new(File::create(path)?);
+        let mut ser = ron::Serializer::with_options(
+            writer,
+            Some(ron::ser::PrettyConfig::default()),
+            ron::options::Options::default(),
+        )?;
+
+        SerializeComponents::<SaveLoadError, SaveMarker>::serialize(
+            &(positions, velocities, lifetimes, sprites),
+            &entities,
+            &markers,
+            &mut ser,
+        )?;
+
+        Ok(())
+    }
+
+    // Restores entities and components previously written by `save_world`,
+    // reusing existing `SaveMarker` ids where an entity is already present.
+    #[allow(clippy::type_complexity)]
+    fn load_world(&mut self, path: &str) -> Result<(), SaveLoadError> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut de = ron::Deserializer::from_str(&contents)?;
+
+        let (entities, mut markers, mut allocator, positions, velocities, lifetimes, sprites): (
+            Entities,
+            WriteStorage<SaveMarker>,
+            Write<SaveMarkerAllocator>,
+            WriteStorage<Position>,
+            WriteStorage<Velocity>,
+            WriteStorage<Lifetime>,
+            WriteStorage<Sprite>,
+        ) = self.world.system_data();
+
+        DeserializeComponents::<SaveLoadError, SaveMarker>::deserialize(
+            &mut (positions, velocities, lifetimes, sprites),
+            &entities,
+            &mut markers,
+            &mut allocator,
+            &mut de,
+        )?;
+
+        Ok(())
+    }
 }
 
 impl<'a> State for GameState<'a> {
-    fn update(&mut self, _ctx: &mut Context) -> tetra::Result {
+    fn update(&mut self, ctx: &mut Context) -> tetra::Result {
+        // `Context` isn't `Send`, so it can't be read from inside a system -
+        // capture the direction pressed this frame into a resource instead.
+        let direction = if input::is_key_down(ctx, Key::Up) {
+            Some(Vec2::new(0, -1))
+        } else if input::is_key_down(ctx, Key::Down) {
+            Some(Vec2::new(0, 1))
+        } else if input::is_key_down(ctx, Key::Left) {
+            Some(Vec2::new(-1, 0))
+        } else if input::is_key_down(ctx, Key::Right) {
+            Some(Vec2::new(1, 0))
+        } else {
+            None
+        };
+        self.world.write_resource::<InputState>().direction = direction;
+
+        if input::is_key_pressed(ctx, Key::F5) {
+            if let Err(e) = self.save_world(SAVE_PATH) {
+                eprintln!("failed to save world: {}", e);
+            }
+        } else if input::is_key_pressed(ctx, Key::F9) {
+            if let Err(e) = self.load_world(SAVE_PATH) {
+                eprintln!("failed to load world: {}", e);
+            }
+        }
+
         // This dispatches all the systems in parallel (but blocking).
-        self.dispatcher.dispatch(&mut self.world);
+        self.dispatcher.dispatch(&self.world);
+        // Apply this tick's `entities.delete` calls (and emit `Removed` events).
+        self.world.maintain();
 
         Ok(())
     }
@@ -149,19 +830,19 @@ impl<'a> State for GameState<'a> {
     fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
         graphics::clear(ctx, Color::BLACK);
 
-        let positions = self.world.read_storage::<Position>();
-        let sprites = self.world.read_storage::<Sprite>();
-        
-        let scale = Vec2::new((SPRITE_SIZE - 1) as f32 , (SPRITE_SIZE - 1) as f32);
-
-        for (position, sprite) in (&positions, &sprites).join() {
-            let pos = Vec2::new(((position.0).x * SPRITE_SIZE) as f32, ((position.0).y * SPRITE_SIZE) as f32);
-                graphics::draw(ctx, &self.spritesheet, DrawParams::new()
-                    .position(pos)
-                    .clip(sprite.rect)
-                    .scale(scale));
+        {
+            let mut render_state = self.world.write_resource::<RenderState>();
+            render_state.ctx = ctx as *mut Context;
+            render_state.texture = &self.spritesheet as *const Texture;
         }
 
+        self.render_dispatcher.dispatch(&self.world);
+
+        {
+            let mut render_state = self.world.write_resource::<RenderState>();
+            render_state.ctx = std::ptr::null_mut();
+            render_state.texture = std::ptr::null();
+        }
 
         Ok(())
     }